@@ -0,0 +1,229 @@
+//! Content-defined chunking of the container tar for server-side dedup.
+//!
+//! Whole-file rsync diffing keeps a single signature of the previous submission
+//! and sends one monolithic diff. Docker image tars shift bytes whenever a layer
+//! changes, which defeats fixed-block rsync. Instead we split the tar with a
+//! rolling hash so boundaries follow the *content*: an inserted byte only
+//! reshapes the chunk that contains it, leaving every other chunk (and its
+//! SHA256) identical. The server can then dedup those unchanged chunks across
+//! submissions.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Minimum chunk size; the first `MIN_SIZE` bytes of each chunk are skipped
+/// before the rolling hash is consulted, so we never emit tiny chunks.
+pub const MIN_SIZE: usize = 64 * 1024;
+/// The average chunk size the normal-zone mask targets.
+pub const NORMAL_SIZE: usize = 256 * 1024;
+/// Hard cap on a chunk; a boundary is forced here regardless of the hash.
+pub const MAX_SIZE: usize = 1024 * 1024;
+
+const NORMAL_BITS: u32 = NORMAL_SIZE.trailing_zeros();
+/// Strict mask used below `NORMAL_SIZE`: more 1-bits makes a boundary harder to
+/// satisfy, biasing chunks up towards the target size.
+const MASK_S: u64 = (1 << (NORMAL_BITS + 2)) - 1;
+/// Lenient mask used past `NORMAL_SIZE`: fewer 1-bits makes a boundary easier to
+/// satisfy, so we cut before hitting `MAX_SIZE`.
+const MASK_L: u64 = (1 << (NORMAL_BITS - 2)) - 1;
+
+/// Per-byte mixing table for the gear rolling hash, derived deterministically
+/// so client and server agree on boundaries.
+static GEAR: [u64; 256] = build_gear_table();
+
+const fn build_gear_table() -> [u64; 256] {
+    // splitmix64 over a fixed seed: deterministic, well-distributed bytes.
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        table[i] = z;
+        i += 1;
+    }
+    table
+}
+
+/// A single content-defined chunk: its position in the tar and its content id.
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    pub offset: usize,
+    pub length: usize,
+    pub hash: String,
+}
+
+/// Splits `data` into content-defined chunks and hashes each with SHA256.
+pub fn split(data: &[u8]) -> Vec<Chunk> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < data.len() {
+        let length = next_boundary(&data[start..]);
+        let slice = &data[start..start + length];
+        let hash = hex::encode(Sha256::digest(slice));
+        chunks.push(Chunk {
+            offset: start,
+            length,
+            hash,
+        });
+        start += length;
+    }
+    chunks
+}
+
+/// Returns the length of the next chunk starting at the front of `data`, using
+/// the FastCDC normalized-chunking scheme: skip the first `MIN_SIZE` bytes, then
+/// look for a boundary with the strict `MASK_S` while below `NORMAL_SIZE`,
+/// relaxing to `MASK_L` past it, and force a cut at `MAX_SIZE`.
+fn next_boundary(data: &[u8]) -> usize {
+    let len = data.len();
+    if len <= MIN_SIZE {
+        return len;
+    }
+    let mut fingerprint: u64 = 0;
+    let mut i = MIN_SIZE;
+
+    let normal = len.min(NORMAL_SIZE);
+    while i < normal {
+        fingerprint = (fingerprint << 1).wrapping_add(GEAR[data[i] as usize]);
+        if (fingerprint & MASK_S) == 0 {
+            return i + 1;
+        }
+        i += 1;
+    }
+
+    let max = len.min(MAX_SIZE);
+    while i < max {
+        fingerprint = (fingerprint << 1).wrapping_add(GEAR[data[i] as usize]);
+        if (fingerprint & MASK_L) == 0 {
+            return i + 1;
+        }
+        i += 1;
+    }
+    max
+}
+
+/// A reassembly manifest entry. Runs of consecutive already-known chunks are
+/// merged into a single `Reuse` entry to cut request overhead, the way
+/// proxmox-backup's `merge_known_chunks` does.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum ManifestEntry {
+    /// A run of chunks the server already has, referenced by content id.
+    Reuse { hashes: Vec<String> },
+    /// A chunk whose body is included in this submission.
+    New { hash: String, length: usize },
+}
+
+/// The ordered list of chunk hashes, as sent to `/api/submit/chunks` for the
+/// server to report which ones it is missing.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChunkQuery {
+    pub hashes: Vec<String>,
+}
+
+/// The server's reply: the subset of queried hashes it does not yet store.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MissingChunks {
+    pub missing: Vec<String>,
+}
+
+/// Builds the reassembly manifest, merging runs of known (non-missing) chunks
+/// into single `Reuse` entries.
+pub fn merge_known_chunks(chunks: &[Chunk], missing: &[String]) -> Vec<ManifestEntry> {
+    let missing: HashSet<&str> = missing.iter().map(|s| s.as_str()).collect();
+    let mut manifest = Vec::new();
+    let mut known_run: Vec<String> = Vec::new();
+
+    for chunk in chunks {
+        if missing.contains(chunk.hash.as_str()) {
+            if !known_run.is_empty() {
+                manifest.push(ManifestEntry::Reuse {
+                    hashes: std::mem::take(&mut known_run),
+                });
+            }
+            manifest.push(ManifestEntry::New {
+                hash: chunk.hash.clone(),
+                length: chunk.length,
+            });
+        } else {
+            known_run.push(chunk.hash.clone());
+        }
+    }
+    if !known_run.is_empty() {
+        manifest.push(ManifestEntry::Reuse { hashes: known_run });
+    }
+    manifest
+}
+
+/// A local index of chunk content ids we have already uploaded to the server.
+///
+/// This replaces the single whole-tar rsync signature. It is persisted as a
+/// plain newline-delimited list of hex content ids.
+///
+/// The index is a local cache, not the source of truth: every submission still
+/// asks the server which chunks it is missing (`ChunkQuery`/`MissingChunks`),
+/// so a chunk the server has garbage-collected is re-uploaded even if the index
+/// still lists it. A chunk absent from the index is uploaded whether or not the
+/// server reports it missing.
+#[derive(Debug, Default)]
+pub struct ChunkIndex {
+    known: HashSet<String>,
+}
+
+impl ChunkIndex {
+    /// Loads the index from `path`, treating a missing file as an empty index.
+    pub fn load(path: &Path) -> Result<ChunkIndex> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => Ok(ChunkIndex {
+                known: contents
+                    .lines()
+                    .map(|l| l.trim())
+                    .filter(|l| !l.is_empty())
+                    .map(|l| l.to_string())
+                    .collect(),
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(ChunkIndex::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Returns the content ids in `chunks` that are absent from the index, in
+    /// order and without duplicates.
+    pub fn missing(&self, chunks: &[Chunk]) -> Vec<String> {
+        let mut seen = HashSet::new();
+        chunks
+            .iter()
+            .map(|c| &c.hash)
+            .filter(|h| !self.known.contains(h.as_str()) && seen.insert(h.as_str()))
+            .cloned()
+            .collect()
+    }
+
+    /// Records every chunk in `chunks` as uploaded.
+    pub fn insert_all(&mut self, chunks: &[Chunk]) {
+        for chunk in chunks {
+            self.known.insert(chunk.hash.clone());
+        }
+    }
+
+    /// Persists the index to `path`.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut contents = String::new();
+        for hash in &self.known {
+            contents.push_str(hash);
+            contents.push('\n');
+        }
+        std::fs::write(path, contents)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,53 @@
+//! Client-side compression of the request body.
+//!
+//! The reqwest client only negotiates compressed *responses*; the container and
+//! chunk bodies we POST go out uncompressed even though Docker tars compress
+//! well. We compress the body here, advertise it with `Content-Encoding`, and
+//! always hash the *uncompressed* bytes so the server-side integrity check and
+//! the `HashingWriter` logic keep working unchanged.
+
+use std::io::Write;
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// The codec used to compress an uploaded body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Default, Deserialize)]
+#[value(rename_all = "lower")]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    /// No compression (historical behavior).
+    None,
+    /// gzip, for broad server compatibility.
+    Gzip,
+    /// zstd, for a better ratio/speed tradeoff.
+    #[default]
+    Zstd,
+}
+
+impl Compression {
+    /// The `Content-Encoding` header value, if any, the server must honor to
+    /// decompress the body.
+    pub fn content_encoding(&self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gzip"),
+            Compression::Zstd => Some("zstd"),
+        }
+    }
+
+    /// Compresses `data`, returning the bytes to put on the wire.
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data).context("Failed to gzip body")?;
+                encoder.finish().context("Failed to finish gzip body")
+            }
+            Compression::Zstd => zstd::encode_all(data, 0).context("Failed to zstd body"),
+        }
+    }
+}
@@ -0,0 +1,17 @@
+mod apply_rsync_diff;
+mod chunking;
+mod client_state;
+mod compression;
+mod docker;
+mod output;
+mod progress;
+mod requests;
+mod resumable;
+mod submit;
+mod token_store;
+
+pub use apply_rsync_diff::apply_rsync_diff;
+pub use client_state::LoginMethod;
+pub use compression::Compression;
+pub use output::OutputFormat;
+pub use submit::{submit, SubmitMode};
@@ -2,7 +2,9 @@ use std::path::PathBuf;
 
 use anyhow::{anyhow, Result};
 use clap::{Args, Parser, Subcommand};
-use test_gadget_client::{apply_rsync_diff, submit, SubmitMode};
+use test_gadget_client::{
+    apply_rsync_diff, submit, Compression, LoginMethod, OutputFormat, SubmitMode,
+};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -42,6 +44,25 @@ struct SubmitCommand {
     keep_last_submission: bool,
     #[arg(long, help = "Asks the server to not actually store the submission. Used for testing.")]
     dry_run: bool,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = OutputFormat::Human,
+        help = "Output format. Use 'json' for newline-delimited JSON events in CI."
+    )]
+    format: OutputFormat,
+    #[arg(
+        long,
+        value_enum,
+        help = "Compression codec for the uploaded body. Defaults to the course config, then zstd."
+    )]
+    compression: Option<Compression>,
+    #[arg(
+        long,
+        value_enum,
+        help = "Login method to use. Defaults to the course config, then an interactive password prompt, falling back to the device flow without a TTY."
+    )]
+    login: Option<LoginMethod>,
 }
 
 #[derive(Subcommand)]
@@ -85,6 +106,9 @@ fn main() -> Result<()> {
                 mode,
                 cmd.keep_last_submission,
                 cmd.dry_run,
+                cmd.format,
+                cmd.compression,
+                cmd.login,
             )
         }
         Commands::Internal(cmd) => match cmd {
@@ -0,0 +1,56 @@
+//! A thin progress-bar abstraction over `indicatif`.
+//!
+//! Byte-level progress is useful for the long build export, hash, and upload
+//! phases, but it must stay out of the way of machine consumers: it is
+//! suppressed automatically when `--format json` is active or when stderr is
+//! not a TTY.
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+/// A progress bar that is a no-op unless progress rendering is enabled.
+pub struct Progress {
+    bar: Option<ProgressBar>,
+}
+
+impl Progress {
+    /// A byte-oriented bar with a known total length.
+    pub fn bytes(total: u64, message: &'static str, enabled: bool) -> Progress {
+        if !enabled {
+            return Progress { bar: None };
+        }
+        let bar = ProgressBar::new(total);
+        bar.set_style(
+            ProgressStyle::with_template(
+                "{msg} [{bar:40}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})",
+            )
+            .expect("valid progress template")
+            .progress_chars("=>-"),
+        );
+        bar.set_message(message);
+        Progress { bar: Some(bar) }
+    }
+
+    /// A spinner for phases whose total size is not known up front.
+    pub fn spinner(message: &'static str, enabled: bool) -> Progress {
+        if !enabled {
+            return Progress { bar: None };
+        }
+        let bar = ProgressBar::new_spinner();
+        bar.set_message(message);
+        Progress { bar: Some(bar) }
+    }
+
+    /// Advances the bar by `n` bytes.
+    pub fn inc(&self, n: u64) {
+        if let Some(bar) = &self.bar {
+            bar.inc(n);
+        }
+    }
+
+    /// Clears the bar once the phase is complete.
+    pub fn finish(&self) {
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+        }
+    }
+}
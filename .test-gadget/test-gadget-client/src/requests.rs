@@ -1,8 +1,76 @@
-pub fn create_reqwest_client() -> Result<reqwest::blocking::Client, reqwest::Error> {
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+/// The protocol version this client speaks. Sent on every request as the
+/// `X-Protocol-Version` header so the server can reject or adapt to out-of-date
+/// clients instead of failing with a cryptic deserialization error.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Failure building a client with custom TLS settings.
+#[derive(Debug)]
+pub enum TlsClientError {
+    /// The configured CA bundle could not be read from disk.
+    CaFile(PathBuf, std::io::Error),
+    /// reqwest rejected the certificate or failed to build the client.
+    Build(reqwest::Error),
+}
+
+/// Builds a client honoring the course's TLS configuration: an extra trusted CA
+/// bundle when `ca_cert_path` is set, and disabled verification (with a loud
+/// warning) when `verify_tls_cert` is explicitly `false`.
+pub fn create_reqwest_client_with_tls(
+    verify_tls_cert: Option<bool>,
+    ca_cert_path: Option<&Path>,
+) -> Result<reqwest::blocking::Client, TlsClientError> {
+    let mut builder = base_builder();
+
+    if let Some(path) = ca_cert_path {
+        let pem = std::fs::read(path).map_err(|e| TlsClientError::CaFile(path.to_path_buf(), e))?;
+        let cert = reqwest::Certificate::from_pem(&pem).map_err(TlsClientError::Build)?;
+        builder = builder.add_root_certificate(cert);
+    }
+
+    if verify_tls_cert == Some(false) {
+        eprintln!("WARNING: TLS certificate verification is disabled for the course server.");
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    builder.build().map_err(TlsClientError::Build)
+}
+
+fn base_builder() -> reqwest::blocking::ClientBuilder {
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert(
+        "X-Protocol-Version",
+        reqwest::header::HeaderValue::from_static(PROTOCOL_VERSION_HEADER),
+    );
     reqwest::blocking::Client::builder()
         .timeout(None)
+        .default_headers(headers)
         .gzip(true)
         .brotli(true)
         .deflate(true)
-        .build()
+}
+
+// HeaderValue::from_static needs a &'static str, so keep a stringified copy of
+// PROTOCOL_VERSION alongside the numeric constant.
+const PROTOCOL_VERSION_HEADER: &str = "1";
+
+/// The server's advertised protocol capability range, fetched from
+/// `/api/version` during submit.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerVersion {
+    pub min_protocol_version: u32,
+    pub max_protocol_version: u32,
+}
+
+impl ServerVersion {
+    /// Returns `true` when this client's protocol version is within the range
+    /// the server supports.
+    pub fn is_compatible(&self) -> bool {
+        PROTOCOL_VERSION >= self.min_protocol_version
+            && PROTOCOL_VERSION <= self.max_protocol_version
+    }
 }
@@ -0,0 +1,197 @@
+//! Resumable, range-based uploads for large container bodies.
+//!
+//! A single `reqwest` POST of a multi-hundred-megabyte container means a dropped
+//! connection restarts the whole transfer. Instead we negotiate an upload
+//! session with the server, push the body in fixed-size ranges, and on a
+//! transient failure ask the server how many bytes it already has and resume
+//! from there rather than re-sending gigabytes. The server verifies the
+//! reassembled SHA256 against the hash we compute before returning the result.
+
+use std::{thread, time::Duration};
+
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use reqwest::blocking::Client;
+use serde::Deserialize;
+
+use crate::progress::Progress;
+
+/// Size of each uploaded range. Large enough to amortize request overhead,
+/// small enough that a retry after a drop re-sends little.
+const RANGE_SIZE: usize = 8 * 1024 * 1024;
+/// Maximum number of retries for a single range before giving up.
+const MAX_RANGE_RETRIES: u32 = 5;
+/// Initial backoff, doubled on each retry.
+const BASE_BACKOFF: Duration = Duration::from_millis(500);
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SessionResponse {
+    upload_id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StatusResponse {
+    received_offset: usize,
+}
+
+/// Uploads `body` to `{base_url}/{endpoint}` resumably and returns the final
+/// response body (the completed-upload JSON, e.g. an `UploadResult`).
+///
+/// `query` and `headers` are attached to the session-creation request so the
+/// server has the sha256, dry-run flag, and any chunk manifest up front.
+pub fn upload(
+    client: &Client,
+    auth_token: &str,
+    base_url: &str,
+    endpoint: &str,
+    body: &[u8],
+    query: &[(&str, &str)],
+    headers: &[(&str, String)],
+    progress: &Progress,
+) -> Result<String> {
+    let upload_id = create_session(client, auth_token, base_url, endpoint, body.len(), query, headers)?;
+    info!("Negotiated upload session {upload_id}.");
+
+    let total = body.len();
+    let mut offset = 0;
+    let mut final_body: Option<String> = None;
+    let mut range_retries = 0;
+
+    while offset < total {
+        let end = (offset + RANGE_SIZE).min(total);
+        match send_range(client, auth_token, base_url, endpoint, &upload_id, &body[offset..end], offset, total) {
+            Ok(response) => {
+                progress.inc((end - offset) as u64);
+                offset = end;
+                range_retries = 0;
+                if offset >= total {
+                    final_body = Some(response);
+                }
+            }
+            Err(e) => {
+                // A 4xx (e.g. a 401 after the token expired mid-upload) is fatal
+                // and must propagate as the original `reqwest::Error` so the
+                // caller's re-login retry can recognize it; only transient
+                // failures are resumable.
+                if is_fatal(&e) {
+                    return Err(e);
+                }
+                range_retries += 1;
+                if range_retries > MAX_RANGE_RETRIES {
+                    return Err(e.context(format!(
+                        "Gave up on range at offset {offset} after {MAX_RANGE_RETRIES} retries"
+                    )));
+                }
+                warn!("Range upload at offset {offset} failed, resuming: {e}");
+                let resumed = resync_offset(client, auth_token, base_url, endpoint, &upload_id)?;
+                // The server may have persisted more (or, on a partial range,
+                // fewer) bytes than we think; resume from its offset. The
+                // progress bar only advances when the server is ahead.
+                progress.inc(resumed.saturating_sub(offset) as u64);
+                offset = resumed;
+            }
+        }
+    }
+    progress.finish();
+
+    final_body.ok_or_else(|| anyhow!("Upload completed without a final server response"))
+}
+
+/// Whether `e` is a fatal client error (a 4xx response, e.g. an auth failure)
+/// that the resume loop must surface to the caller instead of retrying. Network
+/// failures and 5xx responses carry no client-error status and are resumable.
+fn is_fatal(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<reqwest::Error>()
+        .and_then(|re| re.status())
+        .map(|status| status.is_client_error())
+        .unwrap_or(false)
+}
+
+fn create_session(
+    client: &Client,
+    auth_token: &str,
+    base_url: &str,
+    endpoint: &str,
+    size: usize,
+    query: &[(&str, &str)],
+    headers: &[(&str, String)],
+) -> Result<String> {
+    let mut request = client
+        .post(format!("{base_url}/{endpoint}/session"))
+        .bearer_auth(auth_token)
+        .query(query)
+        .query(&[("size", size.to_string())]);
+    for (name, value) in headers {
+        request = request.header(*name, value);
+    }
+    let response: SessionResponse = request.send()?.error_for_status()?.json()?;
+    Ok(response.upload_id)
+}
+
+fn send_range(
+    client: &Client,
+    auth_token: &str,
+    base_url: &str,
+    endpoint: &str,
+    upload_id: &str,
+    slice: &[u8],
+    offset: usize,
+    total: usize,
+) -> Result<String> {
+    let content_range = format!("bytes {}-{}/{}", offset, offset + slice.len() - 1, total);
+    let response = client
+        .patch(format!("{base_url}/{endpoint}/{upload_id}"))
+        .bearer_auth(auth_token)
+        .header("Content-Type", "application/octet-stream")
+        .header("Content-Range", content_range)
+        .body(slice.to_vec())
+        .send()?;
+    // 5xx are transient and trigger a resume; 4xx are fatal.
+    if response.status().is_server_error() {
+        return Err(anyhow!("Server error {} while uploading range", response.status()));
+    }
+    Ok(response.error_for_status()?.text()?)
+}
+
+/// Asks the server how many contiguous bytes it has received, retrying with
+/// exponential backoff since this query can itself hit the transient failure.
+fn resync_offset(
+    client: &Client,
+    auth_token: &str,
+    base_url: &str,
+    endpoint: &str,
+    upload_id: &str,
+) -> Result<usize> {
+    let mut backoff = BASE_BACKOFF;
+    for attempt in 0..MAX_RANGE_RETRIES {
+        thread::sleep(backoff);
+        match query_status(client, auth_token, base_url, endpoint, upload_id) {
+            Ok(offset) => return Ok(offset),
+            Err(e) => {
+                warn!("Status query attempt {} failed: {e}", attempt + 1);
+                backoff *= 2;
+            }
+        }
+    }
+    Err(anyhow!(
+        "Gave up resuming upload {upload_id} after {MAX_RANGE_RETRIES} retries"
+    ))
+}
+
+fn query_status(
+    client: &Client,
+    auth_token: &str,
+    base_url: &str,
+    endpoint: &str,
+    upload_id: &str,
+) -> Result<usize> {
+    let response: StatusResponse = client
+        .get(format!("{base_url}/{endpoint}/{upload_id}/status"))
+        .bearer_auth(auth_token)
+        .send()?
+        .error_for_status()?
+        .json()?;
+    Ok(response.received_offset)
+}
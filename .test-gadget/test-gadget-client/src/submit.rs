@@ -1,10 +1,9 @@
 use std::{
     fs,
-    io::{BufWriter, Seek, Write},
+    io::{BufWriter, Write},
     path::{Path, PathBuf},
     process::Command,
     sync::Arc,
-    thread,
 };
 
 use anyhow::{anyhow, Result};
@@ -14,8 +13,14 @@ use serde::Deserialize;
 use sha2::{Digest, Sha256};
 
 use crate::{
-    client_state::{ClientState, ClientStateError},
-    requests::create_reqwest_client,
+    chunking,
+    client_state::{ClientState, ClientStateError, CourseConfig, LoginMethod},
+    compression::Compression,
+    docker::{self, DockerDaemon},
+    output::{OutputFormat, Reporter},
+    progress::Progress,
+    requests::ServerVersion,
+    resumable,
 };
 
 #[derive(Eq, PartialEq, Clone, Copy)]
@@ -25,14 +30,17 @@ pub enum SubmitMode {
     NoDiff,
 }
 
-// TODO: progress bar
 pub fn submit(
     container_file_override: Option<&Path>,
     server_baseurl_override: Option<&str>,
     mode: SubmitMode,
     keep_last_submission: bool,
     dry_run: bool,
+    format: OutputFormat,
+    compression: Option<Compression>,
+    login: Option<LoginMethod>,
 ) -> Result<()> {
+    let reporter = Reporter::new(format);
     preflight_check_docker()?;
 
     let attempts = 3;
@@ -43,24 +51,30 @@ pub fn submit(
             mode,
             keep_last_submission,
             dry_run,
+            &reporter,
+            compression,
+            login,
         );
         match submit_result {
             Ok(r) => return Ok(r),
             Err(e) => {
                 if is_auth_error(&e) {
-                    println!("Authentication failed.");
-                    ClientState::prompt_for_login_and_init(server_baseurl_override)?;
+                    reporter.phase("authFailed", "Authentication failed.");
+                    ClientState::prompt_for_login_and_init(server_baseurl_override, login)?;
                     continue;
                 } else {
+                    reporter.error(&e.to_string());
                     return Err(e);
                 }
             }
         }
     }
-    return Err(anyhow!(
+    let e = anyhow!(
         "Failed to log in after {} authentication failures",
         attempts
-    ));
+    );
+    reporter.error(&e.to_string());
+    Err(e)
 }
 
 fn is_auth_error(e: &anyhow::Error) -> bool {
@@ -86,6 +100,9 @@ pub fn submit_impl(
     mode: SubmitMode,
     keep_last_submission: bool,
     dry_run: bool,
+    reporter: &Reporter,
+    compression: Option<Compression>,
+    login: Option<LoginMethod>,
 ) -> Result<()> {
     let client_state = ClientState::load();
 
@@ -93,7 +110,7 @@ pub fn submit_impl(
         Ok(c) => c,
         Err(e) => {
             if e.should_prompt_for_token() {
-                ClientState::prompt_for_login_and_init(server_baseurl_override)?
+                ClientState::prompt_for_login_and_init(server_baseurl_override, login)?
             } else {
                 return Err(e.into());
             }
@@ -104,34 +121,37 @@ pub fn submit_impl(
         .course_config
         .get_server_baseurl(server_baseurl_override)
         .ok_or_else(|| anyhow!("{}", ClientStateError::MissingServerBaseUrl))?;
+
+    // A `--compression` flag overrides the course config, which defaults to zstd.
+    let compression = compression.unwrap_or_else(|| client_state.course_config.compression());
+
+    check_protocol_version(&server_baseurl, &client_state.course_config)?;
+
     let container_file = if let Some(p) = container_file_override {
         p.to_path_buf()
     } else {
-        println!("Building container...");
-        build_container(&client_state)?;
-        println!(
-            "Container size: {:.2}MB",
-            (client_state.temp_container_file.metadata()?.len() as f64) / 1024.0 / 1024.0
-        );
+        reporter.phase("building", "Building container...");
+        build_container(&client_state, reporter)?;
+        reporter.container_size(client_state.temp_container_file.metadata()?.len());
         client_state.temp_container_file.clone()
     };
 
-    let upload_result: UploadResult = if mode != SubmitMode::NoDiff
-        && client_state.has_previous_submission()
-    {
-        println!("Uploading container (diff)...");
-        match upload_container_with_diffing(
+    let upload_result: UploadResult = if mode != SubmitMode::NoDiff {
+        reporter.phase("uploading", "Uploading container (chunked)...");
+        match upload_container_with_chunking(
             &client_state,
             &server_baseurl,
             &container_file,
             dry_run,
+            reporter,
+            compression,
         ) {
             Ok(r) => r,
             Err(e) => {
                 if mode == SubmitMode::TryDiffFirst && !is_auth_error(&e) && !is_gone_error(&e) {
-                    println!(
-                        "Failed to upload diff so uploading full container instead: {}",
-                        e
+                    reporter.phase(
+                        "fallbackFull",
+                        &format!("Failed to upload chunks so uploading full container instead: {e}"),
                     );
 
                     upload_container_without_diffing(
@@ -139,6 +159,8 @@ pub fn submit_impl(
                         &server_baseurl,
                         &container_file,
                         dry_run,
+                        reporter,
+                        compression,
                     )?
                 } else {
                     return Err(e);
@@ -146,41 +168,129 @@ pub fn submit_impl(
             }
         }
     } else {
-        println!("Uploading container...");
-        upload_container_without_diffing(&client_state, &server_baseurl, &container_file, dry_run)?
+        reporter.phase("uploading", "Uploading container...");
+        upload_container_without_diffing(
+            &client_state,
+            &server_baseurl,
+            &container_file,
+            dry_run,
+            reporter,
+            compression,
+        )?
     };
 
     if !dry_run {
-        println!("Saving information about this submission...");
-        store_last_submission(&client_state, &container_file, &upload_result.submission_id)?;
+        reporter.phase("saving", "Saving information about this submission...");
+        store_last_submission(&client_state, &upload_result.submission_id)?;
     }
 
     if keep_last_submission {
-        fs::rename(
+        // Keep the submission as the plain tar that was built; nothing reads it
+        // back, so there is no reason to spend time compressing it on disk.
+        let _ = fs::rename(
             &client_state.temp_container_file,
             &client_state.last_submission_container_file,
-        )?;
+        );
     } else {
         let _ = fs::remove_file(&client_state.temp_container_file);
         let _ = fs::remove_file(&client_state.last_submission_container_file);
     }
 
-    println!("Done!");
+    reporter.done(&upload_result.submission_id);
     Ok(())
 }
 
-fn build_container(client_state: &ClientState) -> Result<()> {
+/// Fetches the server's supported protocol range and errors early with a clear
+/// "upgrade required" message when this client is out of date, rather than
+/// letting a later request fail with a cryptic deserialization error.
+fn check_protocol_version(server_baseurl: &str, course_config: &CourseConfig) -> Result<()> {
+    let client = course_config.build_client()?;
+    let version: ServerVersion = client
+        .get(format!("{server_baseurl}/api/version"))
+        .send()?
+        .error_for_status()?
+        .json()?;
+    if !version.is_compatible() {
+        return Err(anyhow!(
+            "This client is out of date and must be upgraded: the server supports protocol \
+             versions {}-{} but this client speaks version {}.",
+            version.min_protocol_version,
+            version.max_protocol_version,
+            crate::requests::PROTOCOL_VERSION,
+        ));
+    }
+    Ok(())
+}
+
+const IMAGE_NAME: &str = "test-gadget-submission:latest";
+const BUILD_PLATFORM: &str = "linux/amd64";
+
+fn build_container(client_state: &ClientState, reporter: &Reporter) -> Result<()> {
     if !PathBuf::from("Dockerfile").exists() {
         return Err(anyhow!("Dockerfile not found in current directory"));
     }
-    let image_name = "test-gadget-submission:latest";
 
+    // Prefer talking to the daemon directly: it lets us stream the export
+    // straight to disk without a round trip through `docker save` and surfaces
+    // structured build progress. Fall back to the CLI when no daemon is
+    // reachable (e.g. a rootless setup only exposing the wrapper).
+    match DockerDaemon::connect() {
+        Ok(daemon) => build_container_via_daemon(&daemon, client_state, reporter),
+        Err(e) => {
+            info!("Docker daemon not reachable ({e}), using the docker CLI.");
+            build_container_via_cli(client_state)
+        }
+    }
+}
+
+fn build_container_via_daemon(
+    daemon: &DockerDaemon,
+    client_state: &ClientState,
+    reporter: &Reporter,
+) -> Result<()> {
+    let context = docker::tar_build_context(Path::new("."))?;
+    daemon.build_image(&context, IMAGE_NAME, BUILD_PLATFORM, |progress| {
+        docker::print_build_progress(progress);
+    })?;
+
+    // The export size is not known until it arrives, so use a byte spinner.
+    let progress = Progress::spinner("Exporting image", reporter.progress_enabled());
+    let out_file = fs::File::create(&client_state.temp_container_file)?;
+    let mut writer = ProgressWriter {
+        inner: BufWriter::new(out_file),
+        progress: &progress,
+    };
+    daemon.export_image(IMAGE_NAME, &mut writer)?;
+    writer.inner.flush()?;
+    progress.finish();
+    Ok(())
+}
+
+/// Wraps a writer to advance a [`Progress`] bar by the number of bytes written.
+struct ProgressWriter<'a, W: Write> {
+    inner: W,
+    progress: &'a Progress,
+}
+
+impl<W: Write> Write for ProgressWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.progress.inc(n as u64);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn build_container_via_cli(client_state: &ClientState) -> Result<()> {
     let build_status = Command::new("docker")
         .arg("build")
         .arg("-t")
-        .arg(image_name)
+        .arg(IMAGE_NAME)
         .arg("--platform")
-        .arg("linux/amd64")
+        .arg(BUILD_PLATFORM)
         .arg(".")
         .status()?;
     if !build_status.success() {
@@ -191,7 +301,7 @@ fn build_container(client_state: &ClientState) -> Result<()> {
         .arg("save")
         .arg("-o")
         .arg(&client_state.temp_container_file)
-        .arg(image_name)
+        .arg(IMAGE_NAME)
         .status()?;
     if !save_status.success() {
         return Err(anyhow!("Failed to export Docker image"));
@@ -201,8 +311,12 @@ fn build_container(client_state: &ClientState) -> Result<()> {
 }
 
 fn preflight_check_docker() -> Result<()> {
-    // Intentionally simple "is Docker installed?" check.
-    // We run this before attempting `docker build` so we can show a clear error message.
+    // We only need *a* way to reach Docker: either the daemon API directly or
+    // the `docker` CLI as a fallback. Check the daemon first, then fall back to
+    // the CLI version probe so we can show a clear error message before building.
+    if docker::daemon_available() {
+        return Ok(());
+    }
     let output = Command::new("docker").arg("--version").output();
     let blurb = "Docker does not seem to be available.\n\n\
 Please install Docker (Docker Desktop or Docker Engine) and ensure the `docker` command works.\n\n";
@@ -228,138 +342,175 @@ Error details: {}\n",
     }
 }
 
-fn upload_container_with_diffing(
+fn upload_container_with_chunking(
     client_state: &ClientState,
     server_baseurl: &str,
     container_file: &Path,
     dry_run: bool,
+    reporter: &Reporter,
+    compression: Compression,
 ) -> Result<UploadResult> {
-    let prev_signature = fs::read(&client_state.last_submission_rsync_signature_file)?;
-    let prev_signature = fast_rsync::Signature::deserialize(prev_signature)?;
-    let prev_signature = prev_signature.index();
-    let last_submission_id = fs::read_to_string(&client_state.last_submission_id_file)?
-        .trim()
-        .to_string();
-    let file = fs::File::open(&container_file)?;
+    let file = fs::File::open(container_file)?;
     let mmap = Arc::new(unsafe { Mmap::map(&file) }?);
 
-    info!("Calculating diff and hash...");
-    let (hash, diff_file) = thread::scope(|s| -> Result<(String, fs::File)> {
-        let hash_thread = {
-            let mmap = mmap.clone();
-            s.spawn(move || {
-                let digest = hex::encode(Sha256::digest(mmap.as_ref()));
-                info!("Hash calculated.");
-                digest
-            })
-        };
-
-        let diff_thread = {
-            let mmap = mmap.clone();
-            s.spawn(move || -> Result<fs::File> {
-                let diff_path = &client_state.temp_container_diff_file;
-                let mut diff_file = fs::OpenOptions::new()
-                    .read(true)
-                    .write(true)
-                    .create(true)
-                    .truncate(true)
-                    .open(diff_path)?;
-                {
-                    let mut diff_writer = BufWriter::new(&mut diff_file);
-                    fast_rsync::diff(&prev_signature, &mmap, &mut diff_writer)?;
-                    diff_writer.flush()?;
-                }
-                info!("Diff calculated.");
-                info!(
-                    "Diff size: {:.2}MB (original size: {:.2}MB)",
-                    (diff_file.metadata()?.len() as f64) / 1024.0 / 1024.0,
-                    (mmap.len() as f64) / 1024.0 / 1024.0
-                );
-                diff_file.rewind()?;
-                Ok(diff_file)
-            })
-        };
-
-        let hash = hash_thread.join().unwrap();
-        let diff_file = diff_thread.join().unwrap()?;
-        Ok((hash, diff_file))
-    })?;
-
-    println!(
-        "Diff size: {:.2}MB",
-        (client_state.temp_container_diff_file.metadata()?.len() as f64) / 1024.0 / 1024.0
+    info!("Splitting container into content-defined chunks...");
+    let chunks = chunking::split(mmap.as_ref());
+    // The hash is always over the uncompressed tar so the server-side integrity
+    // check matches what we store locally.
+    let hash = hash_with_progress(mmap.as_ref(), reporter.progress_enabled());
+    info!("Split into {} chunks.", chunks.len());
+
+    let client = client_state.course_config.build_client()?;
+
+    // Decide which chunk bodies to send by combining two sources: chunks the
+    // local index has no record of uploading, and chunks the server reports
+    // missing, which catches anything it has garbage-collected that our index
+    // still lists. Taking the union keeps us correct (a `Reuse` entry never
+    // references a chunk the server lacks); a chunk absent from the index is
+    // uploaded even when the server already stores it.
+    let mut index = chunking::ChunkIndex::load(&client_state.chunk_index_file)?;
+    let mut missing_hashes: std::collections::HashSet<String> =
+        index.missing(&chunks).into_iter().collect();
+    missing_hashes.extend(query_missing_chunks(
+        &client,
+        &client_state.auth_token,
+        server_baseurl,
+        &chunks,
+    )?);
+    let missing: Vec<String> = missing_hashes.into_iter().collect();
+    info!(
+        "{} of {} chunks are new and need uploading.",
+        missing.len(),
+        chunks.len()
     );
 
-    info!("Beginning upload...");
-    let server_url = format!("{}/api/submit/diff", server_baseurl);
-    let client = create_reqwest_client()?;
-    let response = client
-        .post(server_url)
-        .query(&[
-            ("sha256", &hash),
-            ("prevId", &last_submission_id),
-            ("dryRun", &dry_run.to_string()),
-        ])
-        .bearer_auth(&client_state.auth_token)
-        .header("Content-Type", "application/octet-stream")
-        .body(diff_file)
-        .send()?
-        .error_for_status()?;
-    let result: UploadResult = serde_json::from_str(&response.text()?)?;
-    info!("Diff uploaded successfully.");
+    // Merge runs of already-known chunks before building the reassembly
+    // manifest so we don't pay per-chunk overhead for unchanged layers.
+    let manifest = chunking::merge_known_chunks(&chunks, &missing);
+
+    // Concatenate the bodies of the missing chunks in manifest order.
+    let missing_set: std::collections::HashSet<&str> =
+        missing.iter().map(|s| s.as_str()).collect();
+    let mut bodies = Vec::new();
+    for chunk in &chunks {
+        if missing_set.contains(chunk.hash.as_str()) {
+            bodies.extend_from_slice(&mmap[chunk.offset..chunk.offset + chunk.length]);
+        }
+    }
+
+    let body = compression.compress(&bodies)?;
+    reporter.bytes_sent(body.len() as u64, true);
+    info!("Uploading {} bytes of new chunk bodies...", body.len());
+
+    let mut headers = vec![("X-Chunk-Manifest", serde_json::to_string(&manifest)?)];
+    if let Some(encoding) = compression.content_encoding() {
+        headers.push(("Content-Encoding", encoding.to_string()));
+    }
+    let dry_run_str = dry_run.to_string();
+    let progress = Progress::bytes(body.len() as u64, "Uploading", reporter.progress_enabled());
+    let response_body = resumable::upload(
+        &client,
+        &client_state.auth_token,
+        server_baseurl,
+        "api/submit/chunks/upload",
+        &body,
+        &[("sha256", hash.as_str()), ("dryRun", dry_run_str.as_str())],
+        &headers,
+        &progress,
+    )?;
+    let result: UploadResult = serde_json::from_str(&response_body)?;
+    info!("Chunks uploaded successfully.");
+
+    // Record the chunks the server now has in the local index.
+    if !dry_run {
+        index.insert_all(&chunks);
+        index.save(&client_state.chunk_index_file)?;
+    }
     Ok(result)
 }
 
+/// Asks the server, via `POST /api/submit/chunks`, which of `chunks` it does not
+/// already store. This server-confirmed set is authoritative over the local
+/// index, which can be stale after a server-side garbage collection.
+fn query_missing_chunks(
+    client: &reqwest::blocking::Client,
+    auth_token: &str,
+    server_baseurl: &str,
+    chunks: &[chunking::Chunk],
+) -> Result<Vec<String>> {
+    let mut seen = std::collections::HashSet::new();
+    let hashes: Vec<String> = chunks
+        .iter()
+        .filter(|c| seen.insert(c.hash.as_str()))
+        .map(|c| c.hash.clone())
+        .collect();
+    let query = chunking::ChunkQuery { hashes };
+    let response: chunking::MissingChunks = client
+        .post(format!("{server_baseurl}/api/submit/chunks"))
+        .bearer_auth(auth_token)
+        .json(&query)
+        .send()?
+        .error_for_status()?
+        .json()?;
+    Ok(response.missing)
+}
+
 fn upload_container_without_diffing(
     client_state: &ClientState,
     server_baseurl: &str,
     container_file: &Path,
     dry_run: bool,
+    reporter: &Reporter,
+    compression: Compression,
 ) -> Result<UploadResult> {
     info!("Calculating hash...");
-    let file = fs::File::open(&container_file)?;
-    let hash = {
-        let mmap = Arc::new(unsafe { Mmap::map(&file) }?);
-        hex::encode(Sha256::digest(mmap.as_ref()))
-    };
+    let file = fs::File::open(container_file)?;
+    let mmap = Arc::new(unsafe { Mmap::map(&file) }?);
+    let hash = hash_with_progress(mmap.as_ref(), reporter.progress_enabled());
 
+    let body = compression.compress(mmap.as_ref())?;
+    reporter.bytes_sent(body.len() as u64, false);
     info!("Beginning upload...");
-    let server_url = format!("{}/api/submit", server_baseurl);
-    let client = create_reqwest_client()?;
-    let response = client
-        .post(server_url)
-        .query(&[("sha256", &hash), ("dryRun", &dry_run.to_string())])
-        .bearer_auth(&client_state.auth_token)
-        .header("Content-Type", "application/octet-stream")
-        .body(file)
-        .send()?
-        .error_for_status()?;
-    let result: UploadResult = serde_json::from_str(&response.text()?)?;
+    let client = client_state.course_config.build_client()?;
+
+    let mut headers = Vec::new();
+    if let Some(encoding) = compression.content_encoding() {
+        headers.push(("Content-Encoding", encoding.to_string()));
+    }
+    let dry_run = dry_run.to_string();
+    let progress = Progress::bytes(body.len() as u64, "Uploading", reporter.progress_enabled());
+    let response_body = resumable::upload(
+        &client,
+        &client_state.auth_token,
+        server_baseurl,
+        "api/submit",
+        &body,
+        &[("sha256", hash.as_str()), ("dryRun", dry_run.as_str())],
+        &headers,
+        &progress,
+    )?;
+    let result: UploadResult = serde_json::from_str(&response_body)?;
     info!("Uploaded successfully.");
     Ok(result)
 }
 
-fn store_last_submission(
-    client_state: &ClientState,
-    container_file: &Path,
-    submission_id: &str,
-) -> Result<()> {
-    let file = fs::File::open(&container_file)?;
-    let mmap = Arc::new(unsafe { Mmap::map(&file) }?);
-
-    let mmap = mmap.clone();
-    let signature = fast_rsync::Signature::calculate(
-        mmap.as_ref(),
-        fast_rsync::SignatureOptions {
-            block_size: 512, // TODO: is this a good value?
-            crypto_hash_size: 16,
-        },
-    );
-    fs::write(
-        &client_state.last_submission_rsync_signature_file,
-        signature.serialized(),
-    )?;
+/// Computes the SHA256 of `data` while rendering a byte-level progress bar,
+/// hashing in blocks so the bar advances smoothly on large tars.
+fn hash_with_progress(data: &[u8], progress_enabled: bool) -> String {
+    const BLOCK: usize = 4 * 1024 * 1024;
+    let progress = Progress::bytes(data.len() as u64, "Hashing", progress_enabled);
+    let mut hasher = Sha256::new();
+    for block in data.chunks(BLOCK) {
+        hasher.update(block);
+        progress.inc(block.len() as u64);
+    }
+    progress.finish();
+    hex::encode(hasher.finalize())
+}
 
+fn store_last_submission(client_state: &ClientState, submission_id: &str) -> Result<()> {
+    // The set of uploaded chunks is maintained by the chunked upload path in
+    // its content-id index; here we only need to remember the submission id.
     fs::write(&client_state.last_submission_id_file, submission_id)?;
 
     Ok(())
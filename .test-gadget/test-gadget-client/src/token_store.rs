@@ -0,0 +1,133 @@
+//! At-rest protection for the bearer token.
+//!
+//! Writing the token to `.test-gadget/auth_token` in plaintext is easy to leak
+//! if the project directory is shared, backed up, or accidentally committed.
+//! We prefer the platform secret store (Secret Service / macOS Keychain /
+//! Windows Credential Manager), keyed by the server base URL. When no keyring
+//! is available we fall back to encrypting the token with a key derived from an
+//! interactively prompted passphrase (Argon2id → key, then XChaCha20-Poly1305
+//! with a random stored nonce).
+
+use std::path::Path;
+
+use argon2::Argon2;
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    XChaCha20Poly1305, XNonce,
+};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+const KEYRING_SERVICE: &str = "test-gadget";
+const ENCRYPTED_TOKEN_FILE: &str = "auth_token.enc";
+
+#[derive(Error, Debug)]
+pub enum TokenStoreError {
+    #[error("keyring error: {0}")]
+    Keyring(#[from] keyring::Error),
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    #[error("failed to parse encrypted token file: {0}")]
+    Parse(#[from] serde_json::Error),
+    #[error("failed to read passphrase: {0}")]
+    Passphrase(std::io::Error),
+    #[error("failed to decrypt the stored token (wrong passphrase?)")]
+    Decrypt,
+    #[error("failed to derive an encryption key from the passphrase")]
+    KeyDerivation,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EncryptedToken {
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Stores `token` for `server_url`, preferring the OS keyring and falling back
+/// to a passphrase-encrypted file in `dir`.
+pub fn store(dir: &Path, server_url: &str, token: &str) -> Result<(), TokenStoreError> {
+    match keyring_entry(server_url).and_then(|entry| entry.set_password(token)) {
+        Ok(()) => {
+            eprintln!("Token stored in the system keyring.");
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("System keyring unavailable ({e}); encrypting token with a passphrase.");
+            let passphrase = prompt_passphrase("Passphrase to encrypt the token > ")?;
+            encrypt_to_file(&dir.join(ENCRYPTED_TOKEN_FILE), token, &passphrase)
+        }
+    }
+}
+
+/// Loads the token for `server_url`, returning `Ok(None)` when nothing is
+/// stored anywhere. Keyring lookups win; otherwise the encrypted file is
+/// decrypted after prompting for the passphrase.
+pub fn load(dir: &Path, server_url: &str) -> Result<Option<String>, TokenStoreError> {
+    if let Ok(entry) = keyring_entry(server_url) {
+        match entry.get_password() {
+            Ok(token) => return Ok(Some(token)),
+            Err(keyring::Error::NoEntry) => {}
+            Err(e) => return Err(TokenStoreError::Keyring(e)),
+        }
+    }
+
+    let path = dir.join(ENCRYPTED_TOKEN_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let passphrase = prompt_passphrase("Passphrase to decrypt the token > ")?;
+    decrypt_from_file(&path, &passphrase).map(Some)
+}
+
+fn keyring_entry(server_url: &str) -> Result<keyring::Entry, keyring::Error> {
+    keyring::Entry::new(KEYRING_SERVICE, server_url)
+}
+
+fn prompt_passphrase(prompt: &str) -> Result<String, TokenStoreError> {
+    rpassword::prompt_password(prompt).map_err(TokenStoreError::Passphrase)
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<[u8; 32], TokenStoreError> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|_| TokenStoreError::KeyDerivation)?;
+    Ok(key)
+}
+
+fn encrypt_to_file(path: &Path, token: &str, passphrase: &str) -> Result<(), TokenStoreError> {
+    let mut salt = [0u8; 16];
+    let mut nonce = [0u8; 24];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let key = derive_key(passphrase, &salt)?;
+    let cipher = XChaCha20Poly1305::new((&key).into());
+    let ciphertext = cipher
+        .encrypt(XNonce::from_slice(&nonce), token.as_bytes())
+        .map_err(|_| TokenStoreError::Decrypt)?;
+
+    let encrypted = EncryptedToken {
+        salt: hex::encode(salt),
+        nonce: hex::encode(nonce),
+        ciphertext: hex::encode(ciphertext),
+    };
+    std::fs::write(path, serde_json::to_string(&encrypted)?)?;
+    Ok(())
+}
+
+fn decrypt_from_file(path: &Path, passphrase: &str) -> Result<String, TokenStoreError> {
+    let encrypted: EncryptedToken = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    let salt = hex::decode(&encrypted.salt).map_err(|_| TokenStoreError::Decrypt)?;
+    let nonce = hex::decode(&encrypted.nonce).map_err(|_| TokenStoreError::Decrypt)?;
+    let ciphertext = hex::decode(&encrypted.ciphertext).map_err(|_| TokenStoreError::Decrypt)?;
+
+    let key = derive_key(passphrase, &salt)?;
+    let cipher = XChaCha20Poly1305::new((&key).into());
+    let plaintext = cipher
+        .decrypt(XNonce::from_slice(&nonce), ciphertext.as_ref())
+        .map_err(|_| TokenStoreError::Decrypt)?;
+    String::from_utf8(plaintext).map_err(|_| TokenStoreError::Decrypt)
+}